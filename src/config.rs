@@ -0,0 +1,202 @@
+use crate::{parse_level, Config};
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/rserve.toml";
+
+// Every field optional: a config file only overrides what it sets, and
+// anything it omits falls through to whatever Config::new() already has.
+#[derive(Debug, Default, Deserialize)]
+struct ConfigFile {
+    debug: Option<bool>,
+    s1: Option<bool>,
+    s2: Option<bool>,
+    s3: Option<bool>,
+    log_level: Option<String>,
+    lfile: Option<PathBuf>,
+    log_format: Option<String>,
+    format: Option<String>,
+    notify_backend: Option<String>,
+    notify_url: Option<String>,
+    notify_channel: Option<String>,
+    notify_token_env: Option<String>,
+    export_host: Option<String>,
+    period: Option<u64>,
+    num_measurements: Option<i32>,
+    num_runs: Option<i32>,
+    hw1: Option<PathBuf>,
+    hw2: Option<PathBuf>,
+    rpc_socket: Option<PathBuf>,
+}
+
+fn apply_file(cfg: &mut Config, file: ConfigFile) {
+    if let Some(v) = file.debug {
+        cfg.debug = v;
+    }
+    if let Some(v) = file.s1 {
+        cfg.s1 = v;
+    }
+    if let Some(v) = file.s2 {
+        cfg.s2 = v;
+    }
+    if let Some(v) = file.s3 {
+        cfg.s3 = v;
+    }
+    if let Some(v) = file.log_level.as_deref().and_then(parse_level) {
+        cfg.llevel = v;
+    }
+    if let Some(v) = file.lfile {
+        cfg.lfile = v;
+    }
+    if let Some(v) = file.log_format.as_deref().and_then(|s| s.parse().ok()) {
+        cfg.log_format = v;
+    }
+    if let Some(v) = file.format.as_deref().and_then(|s| s.parse().ok()) {
+        cfg.format = v;
+    }
+    if let Some(v) = file.notify_backend.as_deref().and_then(|s| s.parse().ok()) {
+        cfg.notify_backend = v;
+    }
+    if let Some(v) = file.notify_url {
+        cfg.notify_url = v;
+    }
+    if let Some(v) = file.notify_channel {
+        cfg.notify_channel = v;
+    }
+    if let Some(v) = file.notify_token_env {
+        cfg.notify_token_env = v;
+    }
+    if let Some(v) = file.export_host {
+        cfg.export_host = v;
+    }
+    if let Some(v) = file.period {
+        cfg.period = v;
+    }
+    if let Some(v) = file.num_measurements {
+        cfg.num_measurements = v;
+    }
+    if let Some(v) = file.num_runs {
+        cfg.num_runs = v;
+    }
+    if let Some(v) = file.hw1 {
+        cfg.hw1 = v;
+    }
+    if let Some(v) = file.hw2 {
+        cfg.hw2 = v;
+    }
+    if let Some(v) = file.rpc_socket {
+        cfg.rpc_socket = v;
+    }
+}
+
+// Environment variables sit between the config file and CLI flags:
+// a config file sets the per-site baseline, env vars let a process
+// supervisor (systemd, docker, etc) override it without editing the file.
+fn apply_env(cfg: &mut Config) {
+    if let Ok(v) = env::var("EXPORT_HOST") {
+        cfg.export_host = v;
+    }
+    if let Ok(v) = env::var("NOTIFY_CHANNEL") {
+        cfg.notify_channel = v;
+    }
+    if let Ok(v) = env::var("PERIOD").and_then(|v| v.parse().map_err(|_| env::VarError::NotPresent))
+    {
+        cfg.period = v;
+    }
+    if let Ok(v) =
+        env::var("NUM_MEASUREMENTS").and_then(|v| v.parse().map_err(|_| env::VarError::NotPresent))
+    {
+        cfg.num_measurements = v;
+    }
+    if let Ok(v) =
+        env::var("NUM_RUNS").and_then(|v| v.parse().map_err(|_| env::VarError::NotPresent))
+    {
+        cfg.num_runs = v;
+    }
+    if let Ok(v) = env::var("HW1") {
+        cfg.hw1 = PathBuf::from(v);
+    }
+    if let Ok(v) = env::var("HW2") {
+        cfg.hw2 = PathBuf::from(v);
+    }
+    if let Ok(v) = env::var("RSERVE_LOG_FILE") {
+        cfg.lfile = PathBuf::from(v);
+    }
+    if let Ok(v) = env::var("RSERVE_RPC_SOCKET") {
+        cfg.rpc_socket = PathBuf::from(v);
+    }
+}
+
+impl Config {
+    // Build a Config layering a TOML file (if present) over the built-in
+    // defaults. A missing file is not an error -- not every deployment
+    // ships one, in which case the defaults stand until `cli()` applies
+    // env/CLI overrides on top.
+    pub fn load(path: &Path) -> Config {
+        let mut cfg = Config::new();
+
+        match fs::read_to_string(path) {
+            Ok(text) => match toml::from_str::<ConfigFile>(&text) {
+                Ok(file) => apply_file(&mut cfg, file),
+                Err(e) => eprintln!("config: couldn't parse {}: {e}", path.display()),
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => eprintln!("config: couldn't read {}: {e}", path.display()),
+        }
+
+        apply_env(&mut cfg);
+        cfg
+    }
+}
+
+// Find `--config <path>` in argv without disturbing the rest of cli()'s
+// parsing; the path has to be known before the rest of the config can be
+// built, since it determines the file layer.
+pub fn path_from_args(args: &[String]) -> PathBuf {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--config" {
+            if let Some(path) = iter.next() {
+                return PathBuf::from(path);
+            }
+        }
+    }
+    PathBuf::from(DEFAULT_CONFIG_PATH)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    //$ cargo test -- config_file_layering_test --nocapture
+    fn config_file_layering_test() {
+        let toml = r#"
+            export_host = "collector.example.com:9000"
+            period = 30
+            hw1 = "/dev/ttyUSB3"
+        "#;
+
+        let mut cfg = Config::new();
+        let file: ConfigFile = toml::from_str(toml).unwrap();
+        apply_file(&mut cfg, file);
+
+        assert_eq!(cfg.export_host, "collector.example.com:9000");
+        assert_eq!(cfg.period, 30);
+        assert_eq!(cfg.hw1, PathBuf::from("/dev/ttyUSB3"));
+        // untouched fields keep their defaults
+        assert_eq!(cfg.num_runs, Config::new().num_runs);
+    }
+
+    #[test]
+    //$ cargo test -- config_path_from_args_test --nocapture
+    fn config_path_from_args_test() {
+        let args: Vec<String> = vec!["rserve".into(), "--config".into(), "/tmp/x.toml".into()];
+        assert_eq!(path_from_args(&args), PathBuf::from("/tmp/x.toml"));
+
+        let args: Vec<String> = vec!["rserve".into()];
+        assert_eq!(path_from_args(&args), PathBuf::from(DEFAULT_CONFIG_PATH));
+    }
+}
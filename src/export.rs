@@ -0,0 +1,245 @@
+use crate::{frame, ulog, FrameHeader, StateBuffer, DBG, ERR, WAR};
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Wall-clock milliseconds since the epoch, for the frame header timestamp.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+const INITIAL_BACKOFF_MS: u64 = 1_000;
+const MAX_BACKOFF_MS: u64 = 60_000;
+// Cap how many buffered entries we flush in one go so a long-dropped
+// uplink backfills gradually instead of flooding the collector.
+const MAX_REPLAY_BURST: usize = 64;
+
+struct ExportConn {
+    stream: Option<TcpStream>,
+    // Host the current `stream` is connected to, so a change in
+    // `Config.export_host` is noticed instead of silently reusing a
+    // connection to the old collector.
+    host: Option<String>,
+    backoff_ms: u64,
+    // Connection attempts before this instant are skipped outright.
+    retry_after: Option<Instant>,
+}
+
+impl ExportConn {
+    const fn new() -> Self {
+        Self {
+            stream: None,
+            host: None,
+            backoff_ms: INITIAL_BACKOFF_MS,
+            retry_after: None,
+        }
+    }
+}
+
+static CONN: Mutex<ExportConn> = Mutex::new(ExportConn::new());
+
+// Small time-based jitter so a fleet of sensors that all lost the uplink
+// at once doesn't reconnect in lockstep. Not cryptographic, just enough
+// to desynchronize retries.
+fn jitter_ms(bound: u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    nanos % bound
+}
+
+// Frame a measurement, add it to the backlog and flush as much of the
+// backlog as the connection allows, oldest-first. Never loses data: if
+// the collector is unreachable the entry simply stays buffered for the
+// next call to pick up.
+pub fn export_data(
+    sb: &mut StateBuffer,
+    host: &str,
+    header: &FrameHeader,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut framed = Vec::with_capacity(payload.len() + 16);
+    frame::write_framed(&mut framed, header, payload)?;
+    sb.add(framed);
+    flush(sb, host)
+}
+
+fn flush(sb: &mut StateBuffer, host: &str) -> io::Result<()> {
+    let mut conn = crate::get_guard!(&CONN);
+
+    if conn.stream.is_some() && conn.host.as_deref() != Some(host) {
+        ulog(
+            std::io::stdout(),
+            DBG,
+            format!(
+                "export: host changed from {:?} to {host}, reconnecting",
+                conn.host
+            ),
+        );
+        conn.stream = None;
+        conn.host = None;
+        conn.retry_after = None;
+        conn.backoff_ms = INITIAL_BACKOFF_MS;
+    }
+
+    if let Some(retry_after) = conn.retry_after {
+        if Instant::now() < retry_after {
+            ulog(
+                std::io::stdout(),
+                DBG,
+                String::from("export: still in backoff, buffering"),
+            );
+            return Ok(());
+        }
+    }
+
+    if conn.stream.is_none() {
+        match TcpStream::connect(host) {
+            Ok(stream) => {
+                ulog(
+                    std::io::stdout(),
+                    DBG,
+                    format!("export: connected to {host}"),
+                );
+                conn.stream = Some(stream);
+                conn.host = Some(host.to_string());
+                conn.backoff_ms = INITIAL_BACKOFF_MS;
+                conn.retry_after = None;
+            }
+            Err(e) => {
+                let wait = conn.backoff_ms + jitter_ms(conn.backoff_ms / 4 + 1);
+                ulog(
+                    std::io::stderr(),
+                    WAR,
+                    format!("export: connect to {host} failed, retrying in {wait}ms: {e}"),
+                );
+                conn.retry_after = Some(Instant::now() + Duration::from_millis(wait));
+                conn.backoff_ms = (conn.backoff_ms * 2).min(MAX_BACKOFF_MS);
+                return Err(e);
+            }
+        }
+    }
+
+    for (seq, entry) in sb.pending_batch(MAX_REPLAY_BURST) {
+        let stream = conn.stream.as_mut().expect("just ensured connected");
+        if let Err(e) = send_one(stream, &entry) {
+            ulog(
+                std::io::stderr(),
+                ERR,
+                format!("export: send failed at seq {seq}, dropping connection: {e}"),
+            );
+            conn.stream = None;
+            let wait = conn.backoff_ms + jitter_ms(conn.backoff_ms / 4 + 1);
+            conn.retry_after = Some(Instant::now() + Duration::from_millis(wait));
+            conn.backoff_ms = (conn.backoff_ms * 2).min(MAX_BACKOFF_MS);
+            return Err(e);
+        }
+        sb.mark_exported(seq);
+    }
+
+    Ok(())
+}
+
+fn send_one(stream: &mut TcpStream, framed: &[u8]) -> io::Result<()> {
+    // Already length-prefixed by `frame::write_framed`, so no extra
+    // delimiter is needed on the wire.
+    stream.write_all(framed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SerializeFormat;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    //$ cargo test -- export_replay_test --nocapture
+    fn export_replay_test() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let handle = thread::spawn(move || {
+            use std::io::Read;
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = Vec::new();
+            sock.read_to_end(&mut buf).unwrap();
+            buf
+        });
+
+        let mut sb = StateBuffer::new();
+        let h1 = FrameHeader::new(SerializeFormat::Json, 1, now_millis());
+        let h2 = FrameHeader::new(SerializeFormat::Json, 1, now_millis());
+        export_data(&mut sb, &addr, &h1, b"one").unwrap();
+        export_data(&mut sb, &addr, &h2, b"two").unwrap();
+
+        // Dropping the connection lets the test collector see EOF.
+        {
+            let mut conn = crate::get_guard!(&CONN);
+            conn.stream = None;
+        }
+
+        let received = handle.join().unwrap();
+        let mut cursor = Cursor::new(received);
+        let (rh1, rp1) = frame::read_framed(&mut cursor).unwrap();
+        let (rh2, rp2) = frame::read_framed(&mut cursor).unwrap();
+        assert_eq!((rh1, rp1), (h1, b"one".to_vec()));
+        assert_eq!((rh2, rp2), (h2, b"two".to_vec()));
+        assert_eq!(sb.pending(), 0);
+    }
+
+    #[test]
+    //$ cargo test -- export_reconnects_on_host_change_test --nocapture
+    fn export_reconnects_on_host_change_test() {
+        use std::io::Read;
+
+        let listener_a = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr_a = listener_a.local_addr().unwrap().to_string();
+        let handle_a = thread::spawn(move || {
+            let (mut sock, _) = listener_a.accept().unwrap();
+            let mut buf = Vec::new();
+            sock.read_to_end(&mut buf).unwrap();
+            buf
+        });
+
+        let mut sb = StateBuffer::new();
+        let h1 = FrameHeader::new(SerializeFormat::Json, 9, now_millis());
+        export_data(&mut sb, &addr_a, &h1, b"first-host").unwrap();
+
+        // Start accepting on a second host *before* exporting again: if the
+        // stale connection to `addr_a` were reused instead of reconnected,
+        // this accept would never complete and the test would hang.
+        let listener_b = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr_b = listener_b.local_addr().unwrap().to_string();
+        let handle_b = thread::spawn(move || {
+            let (mut sock, _) = listener_b.accept().unwrap();
+            let mut buf = Vec::new();
+            sock.read_to_end(&mut buf).unwrap();
+            buf
+        });
+
+        let h2 = FrameHeader::new(SerializeFormat::Json, 9, now_millis());
+        export_data(&mut sb, &addr_b, &h2, b"second-host").unwrap();
+
+        // Dropping the connection lets the test collector see EOF.
+        {
+            let mut conn = crate::get_guard!(&CONN);
+            conn.stream = None;
+        }
+
+        let (_, p1) = frame::read_framed(Cursor::new(handle_a.join().unwrap())).unwrap();
+        let (_, p2) = frame::read_framed(Cursor::new(handle_b.join().unwrap())).unwrap();
+        assert_eq!(p1, b"first-host".to_vec());
+        assert_eq!(p2, b"second-host".to_vec());
+        assert_eq!(sb.pending(), 0);
+    }
+}
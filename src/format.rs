@@ -0,0 +1,282 @@
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+/// Wire serialization format for exported measurements, selectable via
+/// `--format` / `Config.format`. Each non-default variant is behind its own
+/// cargo feature so a minimal build only pulls in the codec(s) it needs;
+/// `json` is a default feature since it's `Config::new()`'s default format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerializeFormat {
+    Json,
+    Bincode,
+    Postcard,
+    Cbor,
+}
+
+impl SerializeFormat {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SerializeFormat::Json => "json",
+            SerializeFormat::Bincode => "bincode",
+            SerializeFormat::Postcard => "postcard",
+            SerializeFormat::Cbor => "cbor",
+        }
+    }
+}
+
+impl fmt::Display for SerializeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for SerializeFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(SerializeFormat::Json),
+            "bincode" => Ok(SerializeFormat::Bincode),
+            "postcard" => Ok(SerializeFormat::Postcard),
+            "cbor" => Ok(SerializeFormat::Cbor),
+            _ => Err(format!("format {s} isn't supported")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SerializeError {
+    #[cfg(feature = "json")]
+    Json(serde_json::Error),
+    #[cfg(feature = "bincode")]
+    Bincode(bincode::Error),
+    #[cfg(feature = "postcard")]
+    Postcard(postcard::Error),
+    #[cfg(feature = "cbor")]
+    Cbor(serde_cbor::Error),
+    // The codec for this format wasn't compiled in.
+    Unsupported(SerializeFormat),
+}
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "json")]
+            SerializeError::Json(e) => write!(f, "json serialize: {e}"),
+            #[cfg(feature = "bincode")]
+            SerializeError::Bincode(e) => write!(f, "bincode serialize: {e}"),
+            #[cfg(feature = "postcard")]
+            SerializeError::Postcard(e) => write!(f, "postcard serialize: {e}"),
+            #[cfg(feature = "cbor")]
+            SerializeError::Cbor(e) => write!(f, "cbor serialize: {e}"),
+            SerializeError::Unsupported(fmt) => {
+                write!(f, "format {fmt} wasn't compiled into this build")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SerializeError {}
+
+/// Serialize `data` with the requested wire format. Each arm is feature
+/// gated so a build that only enables e.g. `postcard` doesn't pull in the
+/// other codecs.
+pub fn serialize<T: Serialize>(data: &T, fmt: SerializeFormat) -> Result<Vec<u8>, SerializeError> {
+    // With every codec feature disabled, `data` goes untouched -- every arm
+    // below reduces to `Err(Unsupported)`. Keep that minimal build warning-free.
+    #[cfg(not(any(
+        feature = "json",
+        feature = "bincode",
+        feature = "postcard",
+        feature = "cbor"
+    )))]
+    let _ = data;
+
+    match fmt {
+        SerializeFormat::Json => {
+            #[cfg(feature = "json")]
+            {
+                serde_json::to_vec(data).map_err(SerializeError::Json)
+            }
+            #[cfg(not(feature = "json"))]
+            {
+                Err(SerializeError::Unsupported(fmt))
+            }
+        }
+        SerializeFormat::Bincode => {
+            #[cfg(feature = "bincode")]
+            {
+                bincode::serialize(data).map_err(SerializeError::Bincode)
+            }
+            #[cfg(not(feature = "bincode"))]
+            {
+                Err(SerializeError::Unsupported(fmt))
+            }
+        }
+        SerializeFormat::Postcard => {
+            #[cfg(feature = "postcard")]
+            {
+                postcard::to_allocvec(data).map_err(SerializeError::Postcard)
+            }
+            #[cfg(not(feature = "postcard"))]
+            {
+                Err(SerializeError::Unsupported(fmt))
+            }
+        }
+        SerializeFormat::Cbor => {
+            #[cfg(feature = "cbor")]
+            {
+                serde_cbor::to_vec(data).map_err(SerializeError::Cbor)
+            }
+            #[cfg(not(feature = "cbor"))]
+            {
+                Err(SerializeError::Unsupported(fmt))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_from_str_test() {
+        assert_eq!(
+            "json".parse::<SerializeFormat>().unwrap(),
+            SerializeFormat::Json
+        );
+        assert_eq!(
+            "postcard".parse::<SerializeFormat>().unwrap(),
+            SerializeFormat::Postcard
+        );
+        assert!("carrier-pigeon".parse::<SerializeFormat>().is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn serialize_json_test() {
+        #[derive(Serialize)]
+        struct T {
+            a: u64,
+        }
+
+        let bytes = serialize(&T { a: 7 }, SerializeFormat::Json).unwrap();
+        assert_eq!(bytes, b"{\"a\":7}");
+    }
+
+    #[cfg(not(feature = "json"))]
+    #[test]
+    fn serialize_json_unsupported_test() {
+        #[derive(Serialize)]
+        struct T {
+            a: u64,
+        }
+
+        let err = serialize(&T { a: 1 }, SerializeFormat::Json).unwrap_err();
+        assert!(matches!(
+            err,
+            SerializeError::Unsupported(SerializeFormat::Json)
+        ));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn serialize_bincode_roundtrip_test() {
+        #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct T {
+            a: u64,
+            b: String,
+        }
+
+        let original = T {
+            a: 7,
+            b: String::from("hi"),
+        };
+        let bytes = serialize(&original, SerializeFormat::Bincode).unwrap();
+        let decoded: T = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(decoded, original);
+    }
+
+    #[cfg(not(feature = "bincode"))]
+    #[test]
+    fn serialize_bincode_unsupported_test() {
+        #[derive(Serialize)]
+        struct T {
+            a: u64,
+        }
+
+        let err = serialize(&T { a: 1 }, SerializeFormat::Bincode).unwrap_err();
+        assert!(matches!(
+            err,
+            SerializeError::Unsupported(SerializeFormat::Bincode)
+        ));
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn serialize_postcard_roundtrip_test() {
+        #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct T {
+            a: u64,
+            b: String,
+        }
+
+        let original = T {
+            a: 7,
+            b: String::from("hi"),
+        };
+        let bytes = serialize(&original, SerializeFormat::Postcard).unwrap();
+        let decoded: T = postcard::from_bytes(&bytes).unwrap();
+        assert_eq!(decoded, original);
+    }
+
+    #[cfg(not(feature = "postcard"))]
+    #[test]
+    fn serialize_postcard_unsupported_test() {
+        #[derive(Serialize)]
+        struct T {
+            a: u64,
+        }
+
+        let err = serialize(&T { a: 1 }, SerializeFormat::Postcard).unwrap_err();
+        assert!(matches!(
+            err,
+            SerializeError::Unsupported(SerializeFormat::Postcard)
+        ));
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn serialize_cbor_roundtrip_test() {
+        #[derive(Serialize, serde::Deserialize, Debug, PartialEq)]
+        struct T {
+            a: u64,
+            b: String,
+        }
+
+        let original = T {
+            a: 7,
+            b: String::from("hi"),
+        };
+        let bytes = serialize(&original, SerializeFormat::Cbor).unwrap();
+        let decoded: T = serde_cbor::from_slice(&bytes).unwrap();
+        assert_eq!(decoded, original);
+    }
+
+    #[cfg(not(feature = "cbor"))]
+    #[test]
+    fn serialize_cbor_unsupported_test() {
+        #[derive(Serialize)]
+        struct T {
+            a: u64,
+        }
+
+        let err = serialize(&T { a: 1 }, SerializeFormat::Cbor).unwrap_err();
+        assert!(matches!(
+            err,
+            SerializeError::Unsupported(SerializeFormat::Cbor)
+        ));
+    }
+}
@@ -0,0 +1,152 @@
+use crate::SerializeFormat;
+use std::io::{self, Read, Write};
+
+/// Every frame starts with this so a collector can sanity-check the stream
+/// before trusting the rest of the header.
+pub const MAGIC: [u8; 4] = *b"RSRV";
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Fixed-size header written before each record's payload bytes:
+/// magic(4) | version(1) | format(1) | source_id(2) | timestamp_ms(8) | len(4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub version: u8,
+    pub format: SerializeFormat,
+    pub source_id: u16,
+    pub timestamp_ms: u64,
+}
+
+impl FrameHeader {
+    pub fn new(format: SerializeFormat, source_id: u16, timestamp_ms: u64) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            format,
+            source_id,
+            timestamp_ms,
+        }
+    }
+}
+
+fn format_tag(fmt: SerializeFormat) -> u8 {
+    match fmt {
+        SerializeFormat::Json => 0,
+        SerializeFormat::Bincode => 1,
+        SerializeFormat::Postcard => 2,
+        SerializeFormat::Cbor => 3,
+    }
+}
+
+fn format_from_tag(tag: u8) -> io::Result<SerializeFormat> {
+    match tag {
+        0 => Ok(SerializeFormat::Json),
+        1 => Ok(SerializeFormat::Bincode),
+        2 => Ok(SerializeFormat::Postcard),
+        3 => Ok(SerializeFormat::Cbor),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown format tag {tag}"),
+        )),
+    }
+}
+
+// Write a length-prefixed, versioned record. Unlike the old newline
+// delimiting, a payload containing arbitrary bytes (including `\n`) is
+// framed unambiguously.
+pub fn write_framed<W: Write>(mut w: W, header: &FrameHeader, payload: &[u8]) -> io::Result<()> {
+    w.write_all(&MAGIC)?;
+    w.write_all(&[header.version])?;
+    w.write_all(&[format_tag(header.format)])?;
+    w.write_all(&header.source_id.to_le_bytes())?;
+    w.write_all(&header.timestamp_ms.to_le_bytes())?;
+    w.write_all(&(payload.len() as u32).to_le_bytes())?;
+    w.write_all(payload)?;
+    Ok(())
+}
+
+// Read one record back. Rejects a magic mismatch or an unsupported
+// protocol version rather than guessing at the rest of the stream.
+pub fn read_framed<R: Read>(mut r: R) -> io::Result<(FrameHeader, Vec<u8>)> {
+    let mut magic = [0u8; 4];
+    r.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "bad frame magic",
+        ));
+    }
+
+    let mut version = [0u8; 1];
+    r.read_exact(&mut version)?;
+    if version[0] != PROTOCOL_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported protocol version {}", version[0]),
+        ));
+    }
+
+    let mut tag = [0u8; 1];
+    r.read_exact(&mut tag)?;
+    let format = format_from_tag(tag[0])?;
+
+    let mut id_buf = [0u8; 2];
+    r.read_exact(&mut id_buf)?;
+    let source_id = u16::from_le_bytes(id_buf);
+
+    let mut ts_buf = [0u8; 8];
+    r.read_exact(&mut ts_buf)?;
+    let timestamp_ms = u64::from_le_bytes(ts_buf);
+
+    let mut len_buf = [0u8; 4];
+    r.read_exact(&mut len_buf)?;
+    let len = u32::from_le_bytes(len_buf) as usize;
+
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+
+    Ok((
+        FrameHeader {
+            version: version[0],
+            format,
+            source_id,
+            timestamp_ms,
+        },
+        payload,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    //$ cargo test -- frame_roundtrip_test --nocapture
+    fn frame_roundtrip_test() {
+        let header = FrameHeader::new(SerializeFormat::Cbor, 2, 1_700_000_000_000);
+        let payload = b"hello sensor".to_vec();
+
+        let mut buf = Vec::new();
+        write_framed(&mut buf, &header, &payload).unwrap();
+
+        let (read_header, read_payload) = read_framed(Cursor::new(buf)).unwrap();
+        assert_eq!(read_header, header);
+        assert_eq!(read_payload, payload);
+    }
+
+    #[test]
+    //$ cargo test -- frame_bad_magic_test --nocapture
+    fn frame_bad_magic_test() {
+        let bytes = b"NOPE\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        assert!(read_framed(Cursor::new(bytes)).is_err());
+    }
+
+    #[test]
+    //$ cargo test -- frame_version_mismatch_test --nocapture
+    fn frame_version_mismatch_test() {
+        let header = FrameHeader::new(SerializeFormat::Json, 1, 0);
+        let mut buf = Vec::new();
+        write_framed(&mut buf, &header, b"x").unwrap();
+        buf[4] = PROTOCOL_VERSION + 1; // corrupt the version byte
+        assert!(read_framed(Cursor::new(buf)).is_err());
+    }
+}
@@ -1,37 +1,44 @@
-use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
-use reqwest::Client;
 use serde::Serialize;
 use std::env;
 use std::fs;
+use std::io::stdout;
 use std::io::Write;
-use std::io::{stderr, stdout};
-use std::net::TcpStream;
 use std::path::PathBuf;
 use std::process;
 use std::sync::{mpsc, Arc, LazyLock, Mutex};
 
+mod config;
+mod export;
+mod format;
+mod frame;
+mod notify;
+mod rpc;
+pub use format::{serialize, SerializeError, SerializeFormat};
+pub use frame::{read_framed, write_framed, FrameHeader, PROTOCOL_VERSION};
+pub use notify::{Notifier, NotifyBackend, SlackNotifier, StderrNotifier, WebhookNotifier};
+pub use rpc::serve as rpc_serve;
+
 const NOTIFY_URL: &str = "https://slack.com/api/chat.postMessage";
 const NOTIFY_CHANNEL: &str = "#drn";
 const NOTIFY_ENV_VAR: &str = "APPVIEW_SLACKBOT_TOKEN";
-pub const PERIOD: u64 = 5;
 const MAX_ENTRIES: usize = 288; // Assuming 5 mins per measurement, gives us 24 hours of data
-const EXPORT_HOST: &str = "default.main.musing-faraday-83adewh.cribl.cloud:20000";
-pub const HW1: &str = "/dev/ttyUSB0";
-pub const HW2: &str = "/dev/i2c-1";
+
+// Defaults for the operational knobs that Config::load() can now override
+// per-site via a TOML file, without a rebuild.
+const DEFAULT_PERIOD: u64 = 5;
+const DEFAULT_EXPORT_HOST: &str = "default.main.musing-faraday-83adewh.cribl.cloud:20000";
+const DEFAULT_HW1: &str = "/dev/ttyUSB0";
+const DEFAULT_HW2: &str = "/dev/i2c-1";
+const DEFAULT_NUM_MEASUREMENTS: i32 = 12; // report every 1 hour
+const DEFAULT_NUM_RUNS: i32 = 60;
+const DEFAULT_RPC_SOCKET: &str = "/tmp/rserve.sock";
+
 pub const TEST_DATA: &str = "/tmp/sensor.dat";
 pub const DBG: LogLevel = LogLevel::Debug;
 pub const ERR: LogLevel = LogLevel::Error;
 pub const INF: LogLevel = LogLevel::Info;
 pub const WAR: LogLevel = LogLevel::Warn;
 
-// Could use features. Too confusing
-// DEBUG:
-//pub const NUM_MEASUREMENTS: i32 = 2;
-//pub const NUM_RUNS: i32 = 7;
-
-pub const NUM_MEASUREMENTS: i32 = 12; // report every 1 hour
-pub const NUM_RUNS: i32 = 60;
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
 pub enum LogLevel {
     Trace,
@@ -41,6 +48,33 @@ pub enum LogLevel {
     Error,
 }
 
+/// Output shape for `ulog`: human-readable text, or one JSON object per
+/// line for a log collector to parse.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("log format {s} isn't supported")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonLogLine<'a> {
+    ts: u64,
+    level: String,
+    msg: &'a str,
+}
+
 #[derive(Clone)]
 pub struct Config {
     pub debug: bool,
@@ -49,6 +83,19 @@ pub struct Config {
     pub s3: bool,
     pub llevel: LogLevel,
     pub lfile: PathBuf,
+    pub log_format: LogFormat,
+    pub format: SerializeFormat,
+    pub notify_backend: NotifyBackend,
+    pub notify_url: String,
+    pub notify_channel: String,
+    pub notify_token_env: String,
+    pub export_host: String,
+    pub period: u64,
+    pub num_measurements: i32,
+    pub num_runs: i32,
+    pub hw1: PathBuf,
+    pub hw2: PathBuf,
+    pub rpc_socket: PathBuf,
 }
 
 impl Config {
@@ -60,6 +107,19 @@ impl Config {
             s3: true,
             llevel: LogLevel::Info,
             lfile: PathBuf::from("/tmp/rserve.log"),
+            log_format: LogFormat::Text,
+            format: SerializeFormat::Json,
+            notify_backend: NotifyBackend::Slack,
+            notify_url: String::from(NOTIFY_URL),
+            notify_channel: String::from(NOTIFY_CHANNEL),
+            notify_token_env: String::from(NOTIFY_ENV_VAR),
+            export_host: String::from(DEFAULT_EXPORT_HOST),
+            period: DEFAULT_PERIOD,
+            num_measurements: DEFAULT_NUM_MEASUREMENTS,
+            num_runs: DEFAULT_NUM_RUNS,
+            hw1: PathBuf::from(DEFAULT_HW1),
+            hw2: PathBuf::from(DEFAULT_HW2),
+            rpc_socket: PathBuf::from(DEFAULT_RPC_SOCKET),
         }
     }
 }
@@ -110,8 +170,13 @@ pub struct SensorChannel {
 
 // Implementation used for static store of measurement data
 pub struct StateBuffer {
-    buffer: Vec<String>,
+    buffer: Vec<Vec<u8>>,
     index: usize,
+    // Monotonic count of entries ever added, and a high-water mark of how
+    // many (oldest-first) have been successfully exported. The gap between
+    // the two is the export backlog.
+    total_added: u64,
+    exported: u64,
 }
 
 impl StateBuffer {
@@ -119,23 +184,78 @@ impl StateBuffer {
         Self {
             buffer: Vec::with_capacity(MAX_ENTRIES),
             index: 0,
+            total_added: 0,
+            exported: 0,
         }
     }
 
     // after max size, replace oldest entry
-    pub fn add(&mut self, entry: String) {
+    pub fn add(&mut self, entry: Vec<u8>) {
         if self.buffer.len() < MAX_ENTRIES {
             self.buffer.push(entry);
         } else {
             self.buffer[self.index] = entry;
         }
         self.index = (self.index + 1) % MAX_ENTRIES;
+        self.total_added += 1;
+
+        // The ring only holds MAX_ENTRIES entries. Once the export backlog
+        // grows past that (a sustained outage), the oldest unexported slots
+        // have already been overwritten by newer ones. Advance the
+        // high-water mark past them instead of letting pending_batch()
+        // serve newer data back under their stale sequence numbers.
+        let backlog = self.total_added - self.exported;
+        if backlog > MAX_ENTRIES as u64 {
+            let dropped = backlog - MAX_ENTRIES as u64;
+            self.exported += dropped;
+            ulog(
+                std::io::stderr(),
+                ERR,
+                format!(
+                    "state buffer: export backlog exceeded {MAX_ENTRIES} entries, dropped {dropped} unexported entr{}",
+                    if dropped == 1 { "y" } else { "ies" }
+                ),
+            );
+        }
     }
 
     // returns an iterator
-    pub fn get_all(&self) -> &[String] {
+    pub fn get_all(&self) -> &[Vec<u8>] {
         &self.buffer
     }
+
+    // Number of added entries not yet marked exported.
+    pub fn pending(&self) -> u64 {
+        self.total_added - self.exported
+    }
+
+    // Oldest-first batch of not-yet-exported entries, capped at `max`.
+    // Each entry is paired with its sequence number so the caller can
+    // report back how far it got via `mark_exported` once the send
+    // succeeds, even if the batch is only partially sent.
+    pub fn pending_batch(&self, max: usize) -> Vec<(u64, Vec<u8>)> {
+        let pending = self.pending();
+        if pending == 0 {
+            return Vec::new();
+        }
+
+        let take = pending.min(max as u64);
+        let start_seq = self.total_added - pending; // oldest un-exported entry
+        (0..take)
+            .map(|i| {
+                let seq = start_seq + i;
+                let pos = (seq as usize) % MAX_ENTRIES;
+                (seq, self.buffer[pos].clone())
+            })
+            .collect()
+    }
+
+    // Record that every entry up to and including `through_seq` made it out.
+    pub fn mark_exported(&mut self, through_seq: u64) {
+        if through_seq + 1 > self.exported {
+            self.exported = through_seq + 1;
+        }
+    }
 }
 
 impl Default for StateBuffer {
@@ -172,12 +292,28 @@ pub fn set_cfg(new_cfg: Config) {
 /*
  * Errors are written to the log file defined in cfg.
  */
+// Render one log line in the configured format. Json falls back to the
+// text form if serialization somehow fails, so a logging call never panics.
+fn format_line(fmt: LogFormat, level: LogLevel, msg: &str) -> String {
+    match fmt {
+        LogFormat::Text => format!("[{:?}] {}", level, msg),
+        LogFormat::Json => {
+            let line = JsonLogLine {
+                ts: export::now_millis(),
+                level: format!("{:?}", level),
+                msg,
+            };
+            serde_json::to_string(&line).unwrap_or_else(|_| format!("[{:?}] {}", level, msg))
+        }
+    }
+}
+
 fn perr(cfg: &Config, level: LogLevel, perr: String) -> Result<(), std::io::Error> {
     let mut lfile = fs::OpenOptions::new()
         .create(true)
         .append(true)
         .open(&cfg.lfile)?;
-    let _ = writeln!(lfile, "[{:?}] {}", level, perr);
+    let _ = writeln!(lfile, "{}", format_line(cfg.log_format, level, &perr));
     Ok(())
 }
 
@@ -186,33 +322,34 @@ pub fn ulog<W: std::io::Write>(mut out: W, level: LogLevel, msg: String) {
     match level {
         LogLevel::Trace => {
             if cfg.llevel <= LogLevel::Trace {
-                let _ = writeln!(out, "[{:?}] {}", level, msg);
+                let _ = writeln!(out, "{}", format_line(cfg.log_format, level, &msg));
             };
         }
         LogLevel::Debug => {
             if cfg.llevel <= LogLevel::Debug {
-                let _ = writeln!(out, "[{:?}] {}", level, msg);
+                let _ = writeln!(out, "{}", format_line(cfg.log_format, level, &msg));
             };
         }
         // for now, always emit info, warn & error
         LogLevel::Info => {
-            let _ = writeln!(out, "[{:?}] {}", level, msg);
+            let _ = writeln!(out, "{}", format_line(cfg.log_format, level, &msg));
         }
         LogLevel::Warn => {
-            let _ = writeln!(out, "[{:?}] {}", level, msg);
+            let _ = writeln!(out, "{}", format_line(cfg.log_format, level, &msg));
         }
         LogLevel::Error => {
             // Intent is output to stderr & the log file
-            let _ = writeln!(out, "[{:?}] {}", level, msg);
+            let _ = writeln!(out, "{}", format_line(cfg.log_format, level, &msg));
             let _ = perr(&cfg, level, msg);
         }
     };
 }
 
 pub fn have_hw() -> bool {
+    let cfg = get_cfg();
     let mut hw: bool = false;
 
-    if fs::metadata(HW1).is_ok() & fs::metadata(HW2).is_ok() {
+    if fs::metadata(&cfg.hw1).is_ok() & fs::metadata(&cfg.hw2).is_ok() {
         ulog(stdout(), DBG, String::from("Sensor H/W exists"));
         hw = true;
     }
@@ -246,34 +383,47 @@ fn usage() {
     eprintln!("\t-l | --level Define log level");
     eprintln!("\t\tLogLevels:");
     eprintln!("\t\ttrace|debug|dbg|info|inf|warn|warning|error|err");
+    eprintln!("\t--format Define export serialization format");
+    eprintln!("\t\tFormats: json|bincode|postcard|cbor");
+    eprintln!("\t--log-format Define ulog output format");
+    eprintln!("\t\tFormats: text|json");
+    eprintln!("\t--config <path> Load a TOML config file (default /etc/rserve.toml)");
     process::exit(-1);
 }
 
-fn get_level(lvl: &str) -> LogLevel {
+fn parse_level(lvl: &str) -> Option<LogLevel> {
     match lvl {
-        "trace" => LogLevel::Trace,
-        "debug" => LogLevel::Debug,
-        "dbg" => LogLevel::Debug,
-        "info" => LogLevel::Info,
-        "inf" => LogLevel::Info,
-        "warn" => LogLevel::Warn,
-        "warning" => LogLevel::Warn,
-        "error" => LogLevel::Error,
-        "err" => LogLevel::Error,
-        _ => {
-            eprintln!("Error: log level {lvl} isn't supported");
-            usage();
-            LogLevel::Debug
-        }
+        "trace" => Some(LogLevel::Trace),
+        "debug" | "dbg" => Some(LogLevel::Debug),
+        "info" | "inf" => Some(LogLevel::Info),
+        "warn" | "warning" => Some(LogLevel::Warn),
+        "error" | "err" => Some(LogLevel::Error),
+        _ => None,
     }
 }
 
+fn get_level(lvl: &str) -> LogLevel {
+    parse_level(lvl).unwrap_or_else(|| {
+        eprintln!("Error: log level {lvl} isn't supported");
+        usage();
+        LogLevel::Debug
+    })
+}
+
 // Tried using clap. It's big and complex. This is simple, just a few options.
+//
+// Settings are layered defaults < config file < environment variables <
+// these CLI flags, so a `--config` file covers the per-site basics and a
+// flag here always wins.
 pub fn cli() -> Config {
-    let mut cfg = get_guard!(&CONFIG);
-
     let args: Vec<String> = env::args().collect();
 
+    let config_path = config::path_from_args(&args);
+    let base = Config::load(&config_path);
+
+    let mut cfg = get_guard!(&CONFIG);
+    *cfg = base;
+
     if args.len() > 1 {
         let mut iter = args.iter();
         while let Some(arg) = iter.next() {
@@ -324,6 +474,43 @@ pub fn cli() -> Config {
                         usage();
                     }
                 }
+                "--format" => {
+                    if let Some(fmt) = iter.next() {
+                        match fmt.parse() {
+                            Ok(sf) => cfg.format = sf,
+                            Err(e) => {
+                                eprintln!("Error: {e}");
+                                usage();
+                            }
+                        }
+                    } else {
+                        eprintln!("format requires a serialization format value");
+                        usage();
+                    }
+                }
+                "--log-format" => {
+                    if let Some(fmt) = iter.next() {
+                        match fmt.parse() {
+                            Ok(lf) => cfg.log_format = lf,
+                            Err(e) => {
+                                eprintln!("Error: {e}");
+                                usage();
+                            }
+                        }
+                    } else {
+                        eprintln!("log-format requires a format value");
+                        usage();
+                    }
+                }
+                "--config" => {
+                    // Already consumed by config::path_from_args() above;
+                    // skip its value here so it isn't treated as an
+                    // unknown arg.
+                    if iter.next().is_none() {
+                        eprintln!("config requires a path value");
+                        usage();
+                    }
+                }
                 _ => {
                     eprintln!("arg {} is not valid", arg.as_str());
                     usage();
@@ -373,95 +560,32 @@ pub fn to_json<T: Serialize>(data: &T) -> serde_json::Result<String> {
     serde_json::to_string(data)
 }
 
-// TODO: make the export operation configurable
-pub fn export_data(jdata: &str) -> std::io::Result<()> {
-    // TODO: move the const to cmd line param or env var
-    let server_addr = EXPORT_HOST;
-    let mut stream = TcpStream::connect(server_addr)?;
-
-    ulog(
-        stdout(),
-        DBG,
-        format!("Connected to export server at {}", server_addr),
-    );
-
-    // Send JSON data over the TCP connection
-    stream.write_all(jdata.as_bytes())?;
-    stream.write_all(b"\n")?; // Ensure the server knows the message boundary
-
-    ulog(stdout(), DBG, format!("export_data: Sent: {}", jdata));
-    Ok(())
+// Export a payload against the default Cribl collector, reusing/reconnecting
+// the persistent connection managed by the export module. `source_id`
+// identifies which sensor the payload came from in the framed envelope.
+pub fn export_data(
+    sb: &mut StateBuffer,
+    source_id: u16,
+    fmt: SerializeFormat,
+    payload: &[u8],
+) -> std::io::Result<()> {
+    let cfg = get_cfg();
+    let header = FrameHeader::new(fmt, source_id, export::now_millis());
+    export::export_data(sb, &cfg.export_host, &header, payload)
 }
 
+// Send `message` through whichever backend `Config` currently selects.
 pub async fn notify(message: String) -> bool {
-    let api_key = env::var(NOTIFY_ENV_VAR);
-    let key: String = match api_key {
-        Ok(ekey) => {
-            ulog(stdout(), DBG, String::from("We have an API key"));
-            ekey
-        }
-        Err(e) => {
-            ulog(
-                stderr(),
-                ERR,
-                format!("Failed to send notification: no API key: {e}"),
-            );
-            return false;
-        }
-    };
+    let cfg = get_cfg();
+    let notifier = notify::notifier_for(&cfg);
 
-    let client = Client::new();
-    let channel = NOTIFY_CHANNEL;
-
-    // The payload needed for the API: "token={}&channel={}&text={}",
-    let mut payload = String::new();
-    payload.push_str("token=");
-    payload.push_str(&key);
-    payload.push_str("&channel=");
-    payload.push_str(channel);
-    payload.push_str("&text=");
-    payload.push_str(&message);
-
-    ulog(stdout(), DBG, format!("Notify: {payload}"));
-
-    // Create headers; sending raw text, not json
-    let mut headers = HeaderMap::new();
-    headers.insert(
-        CONTENT_TYPE,
-        HeaderValue::from_static("application/x-www-form-urlencoded"),
+    ulog(
+        stdout(),
+        DBG,
+        format!("Notify via {:?}: {message}", cfg.notify_backend),
     );
 
-    let url = String::from(NOTIFY_URL);
-
-    let response = client
-        .post(url)
-        .headers(headers)
-        .body(payload) // raw plain text body.
-        .send()
-        .await;
-
-    //dbg!(&response);
-    let mut result: bool = false;
-    match response {
-        Ok(hres) => {
-            // The response is an involved json object.
-            // All we want is the value of ok, which is true or false.
-            // The only substring of ':true' is from ok on success.
-            // It's a short cut, just don't need any values in the json object.
-            let success = match hres.text().await {
-                Ok(hrt) => hrt,
-                Err(e) => format!("notify: Error: json conversion: {e}"),
-            };
-
-            if success.contains(":true") {
-                ulog(stdout(), DBG, String::from("Notification Successful"));
-                result = true;
-            }
-        }
-        Err(e) => eprintln!("response error: {e}"),
-    }
-
-    result
+    notifier.send(&message).await
 }
 
 // Create a ctl-c handler that exits the process immediately
@@ -476,6 +600,7 @@ pub fn ctl_c_handler() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::stderr;
 
     #[derive(serde::Serialize)]
     struct TestStruct {
@@ -529,6 +654,27 @@ mod tests {
         assert_eq!(output.trim(), "[Info] Testing log output");
     }
 
+    #[test]
+    //$ cargo test -- json_log_test --nocapture
+    fn json_log_test() {
+        let mut cfg = Config::new();
+        cfg.log_format = LogFormat::Json;
+        set_cfg(cfg);
+
+        let mut buf = Vec::new();
+        ulog(&mut buf, INF, String::from("Testing json log output"));
+
+        let output = String::from_utf8_lossy(&buf);
+        println!("Captured output: {}", output);
+
+        let parsed: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(parsed["level"], "Info");
+        assert_eq!(parsed["msg"], "Testing json log output");
+        assert!(parsed["ts"].is_u64());
+
+        set_cfg(Config::new());
+    }
+
     #[test]
     //$ cargo test --  state_buffer_test
     fn state_buffer_test() {
@@ -537,17 +683,72 @@ mod tests {
 
         let mut i: usize;
         for i in 0..MAX_ENTRIES {
-            buf.add(format!("sb.{i}"));
+            buf.add(format!("sb.{i}").into_bytes());
         }
 
         i = 0;
         for entry in buf.get_all() {
-            let sbs = format!("sb.{i}");
+            let sbs = format!("sb.{i}").into_bytes();
             assert_eq!(entry, &sbs);
             i += 1;
         }
     }
 
+    #[test]
+    //$ cargo test --  state_buffer_replay_test
+    fn state_buffer_replay_test() {
+        let mut buf = StateBuffer::new();
+
+        buf.add(b"one".to_vec());
+        buf.add(b"two".to_vec());
+        buf.add(b"three".to_vec());
+        assert_eq!(buf.pending(), 3);
+
+        let batch = buf.pending_batch(2);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0], (0, b"one".to_vec()));
+        assert_eq!(batch[1], (1, b"two".to_vec()));
+
+        buf.mark_exported(batch.last().unwrap().0);
+        assert_eq!(buf.pending(), 1);
+
+        let rest = buf.pending_batch(8);
+        assert_eq!(rest, vec![(2, b"three".to_vec())]);
+
+        buf.mark_exported(2);
+        assert_eq!(buf.pending(), 0);
+        assert!(buf.pending_batch(8).is_empty());
+    }
+
+    #[test]
+    //$ cargo test --  state_buffer_overflow_test
+    fn state_buffer_overflow_test() {
+        // A sustained outage where nothing is exported can push the
+        // backlog past the ring's capacity. Entries older than MAX_ENTRIES
+        // must be dropped from the backlog, not served back out of
+        // pending_batch() relabeled as older entries than they really are.
+        let mut buf = StateBuffer::new();
+        let total = MAX_ENTRIES + 10;
+        for i in 0..total {
+            buf.add(format!("sb.{i}").into_bytes());
+        }
+
+        assert_eq!(buf.pending(), MAX_ENTRIES as u64);
+
+        let batch = buf.pending_batch(MAX_ENTRIES);
+        assert_eq!(batch.len(), MAX_ENTRIES);
+        // The oldest surviving entry is the one that pushed total_added -
+        // exported back down to MAX_ENTRIES, i.e. entry 10.
+        assert_eq!(batch[0], (10, format!("sb.{}", 10).into_bytes()));
+        assert_eq!(
+            batch[MAX_ENTRIES - 1],
+            ((total - 1) as u64, format!("sb.{}", total - 1).into_bytes())
+        );
+
+        buf.mark_exported(batch.last().unwrap().0);
+        assert_eq!(buf.pending(), 0);
+    }
+
     #[test]
     //$ cargo test --  to_json_test --nocapture
     fn to_json_test() {
@@ -0,0 +1,306 @@
+use crate::{frame, ulog, SerializeFormat, StateBuffer, WebHandlerChannels, DBG, ERR, INF};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// How long to wait for a sensor handler to answer a routed command
+// before giving up and reporting a JSON-RPC error back to the caller.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+
+const PARSE_ERROR: i32 = -32700;
+const INVALID_PARAMS: i32 = -32602;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INTERNAL_ERROR: i32 = -32603;
+
+#[derive(Debug, Deserialize)]
+struct RpcRequest {
+    #[allow(dead_code)]
+    jsonrpc: Option<String>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcError {
+    code: i32,
+    message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RpcResponse {
+    jsonrpc: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+    id: Value,
+}
+
+impl RpcResponse {
+    fn ok(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: Some(result),
+            error: None,
+            id,
+        }
+    }
+
+    fn err(id: Value, code: i32, message: impl Into<String>) -> Self {
+        Self {
+            jsonrpc: "2.0",
+            result: None,
+            error: Some(RpcError {
+                code,
+                message: message.into(),
+            }),
+            id,
+        }
+    }
+}
+
+// Listen on `socket_path` and service JSON-RPC 2.0 requests, one
+// connection and thread per client, until the process exits. Each line is
+// a complete request; each response is written back as one line.
+pub fn serve(
+    channels: Arc<WebHandlerChannels>,
+    state: Arc<Mutex<StateBuffer>>,
+    socket_path: &Path,
+) -> std::io::Result<()> {
+    // A previous run that didn't shut down cleanly can leave the socket
+    // file behind; bind would otherwise fail with AddrInUse.
+    let _ = fs::remove_file(socket_path);
+
+    let listener = UnixListener::bind(socket_path)?;
+    ulog(
+        std::io::stdout(),
+        INF,
+        format!("rpc: listening on {}", socket_path.display()),
+    );
+
+    for incoming in listener.incoming() {
+        match incoming {
+            Ok(stream) => {
+                let channels = Arc::clone(&channels);
+                let state = Arc::clone(&state);
+                thread::spawn(move || handle_conn(stream, &channels, &state));
+            }
+            Err(e) => ulog(std::io::stderr(), ERR, format!("rpc: accept failed: {e}")),
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_conn(stream: UnixStream, channels: &WebHandlerChannels, state: &Mutex<StateBuffer>) {
+    let reader = BufReader::new(stream.try_clone().expect("clone rpc socket"));
+    let mut writer = stream;
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                ulog(std::io::stderr(), ERR, format!("rpc: read failed: {e}"));
+                return;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<RpcRequest>(&line) {
+            Ok(req) => dispatch(channels, state, req),
+            Err(e) => RpcResponse::err(Value::Null, PARSE_ERROR, format!("parse error: {e}")),
+        };
+
+        let Ok(body) = serde_json::to_string(&response) else {
+            ulog(
+                std::io::stderr(),
+                ERR,
+                String::from("rpc: failed to serialize response"),
+            );
+            return;
+        };
+        if writeln!(writer, "{body}").is_err() {
+            return;
+        }
+    }
+}
+
+fn dispatch(
+    channels: &WebHandlerChannels,
+    state: &Mutex<StateBuffer>,
+    req: RpcRequest,
+) -> RpcResponse {
+    ulog(
+        std::io::stdout(),
+        DBG,
+        format!("rpc: {} {:?}", req.method, req.params),
+    );
+
+    match req.method.as_str() {
+        "sensor.read" => match sensor_id(&req.params) {
+            Ok(id) => route(channels, id, None, &req.id),
+            Err(e) => RpcResponse::err(req.id, INVALID_PARAMS, e),
+        },
+        "sensor.cmd" => {
+            let id = match sensor_id(&req.params) {
+                Ok(id) => id,
+                Err(e) => return RpcResponse::err(req.id, INVALID_PARAMS, e),
+            };
+            let cmd = match req.params.get("cmd").and_then(Value::as_str) {
+                Some(cmd) => cmd.to_string(),
+                None => {
+                    return RpcResponse::err(req.id, INVALID_PARAMS, "params.cmd must be a string")
+                }
+            };
+            route(channels, id, Some(cmd), &req.id)
+        }
+        "state.dump" => {
+            let guard = crate::get_guard!(state);
+            let entries: Vec<Value> = guard.get_all().iter().map(|e| decode_entry(e)).collect();
+            RpcResponse::ok(req.id, Value::Array(entries))
+        }
+        other => RpcResponse::err(req.id, METHOD_NOT_FOUND, format!("unknown method {other}")),
+    }
+}
+
+// StateBuffer entries are the raw framed envelopes export.rs writes to the
+// wire, not text -- lossy-stringifying them mangles the binary header and
+// any non-UTF8 payload. Unpack the frame and hand back a structured value
+// instead; a Json payload is embedded as real JSON, anything else (and any
+// frame that fails to parse) is reported as base64 so no bytes are lost.
+fn decode_entry(entry: &[u8]) -> Value {
+    match frame::read_framed(Cursor::new(entry)) {
+        Ok((header, payload)) => {
+            let payload = match header.format {
+                SerializeFormat::Json => serde_json::from_slice::<Value>(&payload)
+                    .unwrap_or_else(|_| Value::String(BASE64.encode(&payload))),
+                _ => Value::String(BASE64.encode(&payload)),
+            };
+            json!({
+                "format": header.format.as_str(),
+                "source_id": header.source_id,
+                "timestamp_ms": header.timestamp_ms,
+                "payload": payload,
+            })
+        }
+        Err(e) => json!({ "error": format!("couldn't decode frame: {e}") }),
+    }
+}
+
+fn sensor_id(params: &Value) -> Result<u16, String> {
+    params
+        .get("id")
+        .and_then(Value::as_u64)
+        .map(|id| id as u16)
+        .ok_or_else(|| String::from("params.id must be a non-negative integer"))
+}
+
+// Send `cmd` (if any) to sensor `id`'s command channel, then wait for its
+// next data-channel reply with a timeout.
+fn route(
+    channels: &WebHandlerChannels,
+    id: u16,
+    cmd: Option<String>,
+    rpc_id: &Value,
+) -> RpcResponse {
+    let (cmd_tx, data_rx): (&mpsc::Sender<String>, &Arc<Mutex<mpsc::Receiver<String>>>) = match id {
+        1 => (&channels.s1_cmd_tx, &channels.s1_data_rx),
+        2 => (&channels.s2_cmd_tx, &channels.s2_data_rx),
+        3 => (&channels.s3_cmd_tx, &channels.s3_data_rx),
+        _ => return RpcResponse::err(rpc_id.clone(), INVALID_PARAMS, format!("no sensor {id}")),
+    };
+
+    if let Some(cmd) = cmd {
+        if let Err(e) = cmd_tx.send(cmd) {
+            return RpcResponse::err(
+                rpc_id.clone(),
+                INTERNAL_ERROR,
+                format!("sensor {id} command channel closed: {e}"),
+            );
+        }
+    }
+
+    let rx = crate::get_guard!(data_rx);
+    match rx.recv_timeout(REPLY_TIMEOUT) {
+        Ok(data) => RpcResponse::ok(rpc_id.clone(), Value::String(data)),
+        Err(mpsc::RecvTimeoutError::Timeout) => RpcResponse::err(
+            rpc_id.clone(),
+            INTERNAL_ERROR,
+            format!("sensor {id} didn't reply within {REPLY_TIMEOUT:?}"),
+        ),
+        Err(mpsc::RecvTimeoutError::Disconnected) => RpcResponse::err(
+            rpc_id.clone(),
+            INTERNAL_ERROR,
+            format!("sensor {id} data channel closed"),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    //$ cargo test -- sensor_id_test --nocapture
+    fn sensor_id_test() {
+        assert_eq!(sensor_id(&serde_json::json!({"id": 2})), Ok(2));
+        assert!(sensor_id(&serde_json::json!({})).is_err());
+        assert!(sensor_id(&serde_json::json!({"id": "nope"})).is_err());
+    }
+
+    #[test]
+    //$ cargo test -- rpc_request_parse_test --nocapture
+    fn rpc_request_parse_test() {
+        let req: RpcRequest =
+            serde_json::from_str(r#"{"method":"sensor.read","params":{"id":1},"id":7}"#).unwrap();
+        assert_eq!(req.method, "sensor.read");
+        assert_eq!(req.id, serde_json::json!(7));
+    }
+
+    #[test]
+    //$ cargo test -- decode_entry_json_test --nocapture
+    fn decode_entry_json_test() {
+        let header = crate::FrameHeader::new(SerializeFormat::Json, 3, 1_700_000_000_000);
+        let mut buf = Vec::new();
+        frame::write_framed(&mut buf, &header, br#"{"t":1.5}"#).unwrap();
+
+        let decoded = decode_entry(&buf);
+        assert_eq!(decoded["format"], "json");
+        assert_eq!(decoded["source_id"], 3);
+        assert_eq!(decoded["timestamp_ms"], 1_700_000_000_000u64);
+        assert_eq!(decoded["payload"], serde_json::json!({"t": 1.5}));
+    }
+
+    #[test]
+    //$ cargo test -- decode_entry_binary_test --nocapture
+    fn decode_entry_binary_test() {
+        let header = crate::FrameHeader::new(SerializeFormat::Cbor, 1, 0);
+        let payload = vec![0xDE, 0xAD, 0xBE, 0xEF];
+        let mut buf = Vec::new();
+        frame::write_framed(&mut buf, &header, &payload).unwrap();
+
+        let decoded = decode_entry(&buf);
+        assert_eq!(decoded["format"], "cbor");
+        assert_eq!(decoded["payload"], BASE64.encode(&payload));
+    }
+
+    #[test]
+    //$ cargo test -- decode_entry_bad_frame_test --nocapture
+    fn decode_entry_bad_frame_test() {
+        let decoded = decode_entry(b"not a frame");
+        assert!(decoded["error"].is_string());
+    }
+}